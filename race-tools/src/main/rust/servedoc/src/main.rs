@@ -19,8 +19,10 @@
 extern crate lazy_static;
 
 use warp::Filter;
+use warp::http::StatusCode;
 use warp::path::FullPath;
 use std::fs;
+use std::path::{Component, Path, PathBuf};
 use structopt::StructOpt;
 
 #[derive(Clone,Debug,StructOpt)]
@@ -37,6 +39,7 @@ struct Opt {
 
 lazy_static! {
     static ref OPT: Opt = Opt::from_args();
+    static ref ROOT: PathBuf = fs::canonicalize(OPT.root.as_str()).unwrap_or_else(|_| PathBuf::from(OPT.root.as_str()));
 }
 
 /// simple web server to serve target/doc contents on port 8080
@@ -50,44 +53,44 @@ async fn main() {
     let routes = warp::fs::dir(OPT.root.as_str())
         .or(warp::path::full().map( move |fp: FullPath| {
                 let fps = fp.as_str();
-                let p = format!("{}{}", OPT.root, fps);
-        
-                if let Ok(metadata) = fs::metadata(p.as_str()) {
+                let p = match resolve_path(ROOT.as_path(), fps) {
+                    Some(p) => p,
+                    None => return warp::reply::with_status( warp::reply::html( format!("access to {} denied", fps)), StatusCode::FORBIDDEN)
+                };
+
+                if let Ok(metadata) = fs::metadata(&p) {
                     if metadata.is_dir() {
-                        let mut response = init_response(&fps);
-                        for entry in fs::read_dir(p.as_str()).unwrap() {
-                            if let Ok(entry) = entry {
-        
-                                let path_buf = entry.path();
-                                let path = path_buf.as_path();
-            
-                                if let Ok(metadata) = entry.metadata() {
-                                    if let Some(fname) = path.file_name() {
-                                        if let Some(fname) = fname.to_str() {
-                                            if metadata.is_file() {
-                                                if let Some(ext) = path.extension() {
-                                                    if ext == "html" {
-                                                        add_link(&mut response, "📄️ ", &fps, &fname);
-                                                    }
+                        let mut response = init_response(fps);
+                        for entry in fs::read_dir(&p).unwrap().flatten() {
+
+                            let path_buf = entry.path();
+                            let path = path_buf.as_path();
+
+                            if let Ok(metadata) = entry.metadata() {
+                                if let Some(fname) = path.file_name() {
+                                    if let Some(fname) = fname.to_str() {
+                                        if metadata.is_file() {
+                                            if let Some(ext) = path.extension() {
+                                                if ext == "html" {
+                                                    add_link(&mut response, "📄️ ", fps, fname);
                                                 }
-                                            } else if metadata.is_dir() {
-                                                add_link(&mut response, "📁️ ", &fps, &fname);  // \u{1f4c1}
                                             }
+                                        } else if metadata.is_dir() {
+                                            add_link(&mut response, "📁️ ", fps, fname);  // \u{1f4c1}
                                         }
                                     }
                                 }
                             }
                         }
                         finish_response(&mut response);
-                        warp::reply::html(response)
+                        warp::reply::with_status( warp::reply::html(response), StatusCode::OK)
         
                     } else {
-                        warp::reply::html( format!("can't access {}", p.as_str()))
-                        //warp::reply::with_status( format!("can't access {}", p.as_str()), warp::http::StatusCode::FORBIDDEN)  // otherwise warp::fs::dir() should have served it
+                        // otherwise warp::fs::dir() should have served it
+                        warp::reply::with_status( warp::reply::html( format!("can't access {}", fps)), StatusCode::FORBIDDEN)
                     }
                 } else {
-                    warp::reply::html( format!("don't know about {}", p.as_str()))
-                    //warp::reply::with_status( format!("don't know about {}", p.as_str()), warp::http::StatusCode::NOT_FOUND)
+                    warp::reply::with_status( warp::reply::html( format!("don't know about {}", fps)), StatusCode::NOT_FOUND)
                 }
             })
         );
@@ -106,6 +109,55 @@ async fn main() {
     }
 }
 
+/// map a (still percent-encoded) request path to a file system path within root.
+/// '.' and '..' are resolved lexically, i.e. without touching the file system. Returns None if the
+/// request path is malformed or would escape root, either lexically or through a symlink
+fn resolve_path (root: &Path, req_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode(req_path)?;
+    if decoded.contains('\0') || decoded.contains('\\') {
+        return None
+    }
+
+    let mut path = root.to_path_buf();
+    let mut depth = 0;
+    for c in Path::new(decoded.as_str()).components() {
+        match c {
+            Component::Normal(s) => { path.push(s); depth += 1; }
+            Component::ParentDir => {
+                if depth == 0 { return None }
+                path.pop();
+                depth -= 1;
+            }
+            Component::CurDir | Component::RootDir => {}
+            Component::Prefix(_) => return None
+        }
+    }
+
+    // lexically inside root - make sure this still holds once symlinks are resolved
+    match fs::canonicalize(&path) {
+        Ok(canonical) => if canonical.starts_with(root) { Some(canonical) } else { None },
+        Err(_) => Some(path) // does not exist, caller reports it as unknown
+    }
+}
+
+/// decode %XX escapes of a URL path. Returns None for incomplete escapes or if the result is not UTF-8
+fn percent_decode (s: &str) -> Option<String> {
+    let bs = s.as_bytes();
+    let mut buf: Vec<u8> = Vec::with_capacity(bs.len());
+    let mut i = 0;
+    while i < bs.len() {
+        if bs[i] == b'%' {
+            let hex = s.get(i+1..i+3)?;
+            buf.push( u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            buf.push(bs[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(buf).ok()
+}
+
 fn init_response (path: &str) -> String {
     let mut s = String::new();
     s.push_str("<html>\n");
@@ -133,4 +185,52 @@ fn finish_response (response: &mut String) {
     response.push_str("</ul>\n");
     response.push_str("</body>\n");
     response.push_str("</html>");
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn test_root () -> PathBuf {
+        let root = env::temp_dir().join(format!("servedoc-test-{}", std::process::id()));
+        fs::create_dir_all(root.join("doc/sub")).unwrap();
+        fs::canonicalize(&root).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_within_root() {
+        let root = test_root();
+        assert_eq!( resolve_path(&root, "/"), Some(root.clone()));
+        assert_eq!( resolve_path(&root, "/doc/sub"), Some(root.join("doc/sub")));
+        assert_eq!( resolve_path(&root, "/doc/./sub/../sub"), Some(root.join("doc/sub")));
+        assert_eq!( resolve_path(&root, "/doc/sub/.."), Some(root.join("doc")));
+        assert_eq!( resolve_path(&root, "/doc%2Fsub"), Some(root.join("doc/sub")));
+        assert_eq!( resolve_path(&root, "/not-there"), Some(root.join("not-there")));
+    }
+
+    #[test]
+    fn test_reject_traversal() {
+        let root = test_root();
+        assert_eq!( resolve_path(&root, "/.."), None);
+        assert_eq!( resolve_path(&root, "/../etc/passwd"), None);
+        assert_eq!( resolve_path(&root, "/doc/../../etc/passwd"), None);
+        assert_eq!( resolve_path(&root, "/doc/sub/../../.."), None);
+        assert_eq!( resolve_path(&root, "/%2e%2e/etc/passwd"), None);
+        assert_eq!( resolve_path(&root, "/%2E%2E%2Fetc%2Fpasswd"), None);
+        assert_eq!( resolve_path(&root, "/doc/..%2f..%2fetc"), None);
+        assert_eq!( resolve_path(&root, "/..%5c..%5cetc"), None);
+        assert_eq!( resolve_path(&root, "/doc%00"), None);
+        assert_eq!( resolve_path(&root, "/doc%2"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reject_symlink_escape() {
+        let root = test_root();
+        let link = root.join("doc/escape");
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink(env::temp_dir(), &link).unwrap();
+        assert_eq!( resolve_path(&root, "/doc/escape"), None);
+    }
+}